@@ -10,6 +10,80 @@ impl CargoResolver {
     pub fn new() -> Self {
         Self {}
     }
+
+    /// Resolves `dep_target` (a `cargo://name@bucket` build-dependency) to
+    /// its source and compiles its `src/lib.rs` into an rlib, so `build.rs`
+    /// can `--extern` against it - without this, any build script that
+    /// actually uses a build-dependency (`cc`, `bindgen`, ...) would fail to
+    /// compile, since `build_dependencies` was otherwise only ever recorded
+    /// in `Config` for the scheduler, never made available to the script's
+    /// own compilation. Reuses `self.resolve()`, so nested build scripts
+    /// (a build-dependency that itself has a build.rs) are handled too.
+    fn resolve_and_compile_build_dependency(
+        &self,
+        context: &Context,
+        dep_target: &str,
+    ) -> std::io::Result<(String, std::path::PathBuf)> {
+        let config = self.resolve(context.with_target(dep_target), dep_target)?;
+        let crate_name = dep_target
+            .strip_prefix("cargo://")
+            .unwrap_or(dep_target)
+            .split('@')
+            .next()
+            .unwrap_or(dep_target)
+            .to_string();
+        // rustc rejects hyphens in `--crate-name`/`--extern` (Cargo itself
+        // always normalizes to underscores here too), so a hyphenated
+        // build-dependency like `pkg-config` needs this before it's usable.
+        let sanitized_name = sanitize_crate_name(&crate_name);
+
+        let lib_root = config
+            .sources
+            .iter()
+            .find(|s| s.ends_with("/lib.rs") || s.ends_with("\\lib.rs"))
+            .cloned()
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("build-dependency `{crate_name}` has no src/lib.rs"),
+                )
+            })?;
+
+        // Under `context.cache_dir` directly, this would sit outside the
+        // sandbox's bind-mounts (only `working_directory()`/`scratch_dir()`
+        // are mounted in) and every write/read here would fail under
+        // `NamespaceSandbox`; `scratch_dir()` is already mounted for this
+        // same `context`, since that's what the `@rust_compiler` call below
+        // runs under.
+        let out_dir = context.scratch_dir().join("build-deps");
+        std::fs::create_dir_all(&out_dir).ok();
+        let rlib_path = out_dir.join(format!("lib{sanitized_name}.rlib"));
+
+        if !rlib_path.exists() {
+            context.actions.run_process(
+                context,
+                "@rust_compiler",
+                &[
+                    "--crate-type",
+                    "lib",
+                    "--crate-name",
+                    &sanitized_name,
+                    lib_root.as_str(),
+                    "-o",
+                    &rlib_path.to_string_lossy(),
+                ],
+            )?;
+        }
+
+        Ok((sanitized_name, rlib_path))
+    }
+}
+
+/// Normalizes a crate name for consumption by rustc flags that reject
+/// hyphens (`--crate-name`, `--extern name=path`) - the same convention
+/// Cargo itself applies before invoking `rustc`.
+fn sanitize_crate_name(name: &str) -> String {
+    name.replace('-', "_")
 }
 
 fn get_rust_files(
@@ -45,7 +119,27 @@ fn parse_lockstring(l: &str) -> (&str, Vec<&str>) {
 
 #[derive(Debug)]
 struct CargoToml {
-    dependencies: Vec<String>,
+    /// `(name, version requirement)` pairs, the latter defaulting to `"*"`
+    /// for dependencies with no declared `version` (e.g. git/path deps).
+    dependencies: Vec<(String, String)>,
+    build_dependencies: Vec<(String, String)>,
+    /// Path (relative to the crate root) of its `build.rs`, if it has one.
+    build_script: Option<String>,
+}
+
+/// Reads the version requirement out of a dependency table entry, following
+/// both the shorthand (`serde = "1.0"`) and long (`serde = { version =
+/// "1.0", features = [...] }`) forms. Defaults to `"*"` when there's no
+/// `version` key at all (git/path dependencies).
+fn dep_version_req(v: &toml::Value) -> String {
+    match v {
+        toml::Value::String(s) => s.clone(),
+        toml::Value::Table(t) => match t.get("version") {
+            Some(toml::Value::String(s)) => s.clone(),
+            _ => "*".to_string(),
+        },
+        _ => "*".to_string(),
+    }
 }
 
 fn parse_cargo_toml(
@@ -95,8 +189,14 @@ fn parse_cargo_toml(
         .flatten()
         .chain(target_deps_iter);
 
+    // Every declared dependency's version requirement, regardless of
+    // whether it's optional - optional deps discovered later via the
+    // `[features]` table still need a requirement to resolve against.
+    let mut dep_versions: HashMap<String, String> = HashMap::new();
     let mut dependencies = Vec::new();
     for (k, v) in deps_table_iter {
+        dep_versions.insert(k.to_string(), dep_version_req(v));
+
         // Exclude optional dependencies
         if let toml::Value::Table(t) = v {
             if matches!(v.get("optional"), Some(toml::Value::Boolean(true))) {
@@ -151,15 +251,329 @@ fn parse_cargo_toml(
 
     dependencies.extend(optional_deps.into_iter());
 
-    Ok(CargoToml { dependencies })
+    let dependencies: Vec<(String, String)> = dependencies
+        .into_iter()
+        .map(|name| {
+            let req = dep_versions.get(&name).cloned().unwrap_or_else(|| "*".to_string());
+            (name, req)
+        })
+        .collect();
+
+    let mut build_dependencies = Vec::new();
+    if let Some(toml::Value::Table(t)) = table.get("build-dependencies") {
+        for (k, v) in t {
+            if let toml::Value::Table(vt) = v {
+                if matches!(vt.get("optional"), Some(toml::Value::Boolean(true))) {
+                    continue;
+                }
+            }
+            build_dependencies.push((k.to_string(), dep_version_req(v)));
+        }
+    }
+
+    let build_script = match table.get("package").and_then(|p| p.get("build")) {
+        Some(toml::Value::String(path)) => Some(path.clone()),
+        Some(toml::Value::Boolean(false)) => None,
+        _ => {
+            let default_build_rs = filename
+                .parent()
+                .map(|dir| dir.join("build.rs"))
+                .filter(|p| p.exists());
+            default_build_rs.map(|_| "build.rs".to_string())
+        }
+    };
+
+    Ok(CargoToml {
+        dependencies,
+        build_dependencies,
+        build_script,
+    })
+}
+
+/// A parsed `cfg(...)` predicate, as found in `[target.'cfg(...)'.dependencies]` tables.
+#[derive(Debug, PartialEq)]
+enum CfgExpr {
+    /// A bare identifier, e.g. `unix` or `windows`.
+    Ident(String),
+    /// A key/value comparison, e.g. `target_os = "linux"`.
+    KeyValue(String, String),
+    All(Vec<CfgExpr>),
+    Any(Vec<CfgExpr>),
+    Not(Box<CfgExpr>),
+}
+
+fn cfg_parse_error<S: Into<String>>(message: S) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.into())
+}
+
+/// Splits `s` on top-level occurrences of `sep`, ignoring anything nested inside
+/// parentheses or double-quoted strings.
+fn split_top_level(s: &str, sep: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0i32;
+    let mut in_quotes = false;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '(' if !in_quotes => depth += 1,
+            ')' if !in_quotes => depth -= 1,
+            c if c == sep && depth == 0 && !in_quotes => {
+                parts.push(&s[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+/// Finds the index of a top-level `=` in `s` (not nested in parens/quotes), if any.
+fn find_top_level_eq(s: &str) -> Option<usize> {
+    let mut depth = 0i32;
+    let mut in_quotes = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '(' if !in_quotes => depth += 1,
+            ')' if !in_quotes => depth -= 1,
+            '=' if depth == 0 && !in_quotes => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// If `s` is a call to `name(...)` with balanced parens for the entire string,
+/// returns the contents between the outer parens.
+fn strip_call<'a>(s: &'a str, name: &str) -> Option<&'a str> {
+    let prefix = format!("{name}(");
+    if !s.starts_with(&prefix) || !s.ends_with(')') {
+        return None;
+    }
+    let inner = &s[prefix.len()..s.len() - 1];
+
+    // Make sure the opening paren we stripped is actually matched by the
+    // closing paren we stripped, i.e. parens never balance to zero before
+    // the very end of `s`.
+    let mut depth = 0i32;
+    let mut in_quotes = false;
+    for c in inner.chars() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '(' if !in_quotes => depth += 1,
+            ')' if !in_quotes => {
+                depth -= 1;
+                if depth < 0 {
+                    return None;
+                }
+            }
+            _ => {}
+        }
+    }
+    if depth != 0 || in_quotes {
+        return None;
+    }
+    Some(inner)
+}
+
+fn parse_cfg_expr(input: &str) -> std::io::Result<CfgExpr> {
+    let s = input.trim();
+    if s.is_empty() {
+        return Err(cfg_parse_error("empty cfg() expression"));
+    }
+
+    if let Some(paren) = s.find('(') {
+        let name = &s[..paren];
+        if name == "all" || name == "any" || name == "not" {
+            let inner = strip_call(s, name)
+                .ok_or_else(|| cfg_parse_error(format!("unbalanced parentheses in `{s}`")))?;
+            let children = split_top_level(inner, ',')
+                .into_iter()
+                .map(str::trim)
+                .filter(|part| !part.is_empty())
+                .map(parse_cfg_expr)
+                .collect::<std::io::Result<Vec<_>>>()?;
+
+            return match name {
+                "all" => Ok(CfgExpr::All(children)),
+                "any" => Ok(CfgExpr::Any(children)),
+                "not" => {
+                    let mut children = children;
+                    if children.len() != 1 {
+                        return Err(cfg_parse_error(format!(
+                            "not() requires exactly one argument, found {}",
+                            children.len()
+                        )));
+                    }
+                    Ok(CfgExpr::Not(Box::new(children.remove(0))))
+                }
+                _ => unreachable!(),
+            };
+        }
+    }
+
+    if let Some(eq) = find_top_level_eq(s) {
+        let key = s[..eq].trim();
+        let value = s[eq + 1..].trim();
+        if key.is_empty() {
+            return Err(cfg_parse_error(format!("missing key in `{s}`")));
+        }
+        if value.len() < 2 || !value.starts_with('"') || !value.ends_with('"') {
+            return Err(cfg_parse_error(format!("expected a quoted value in `{s}`")));
+        }
+        return Ok(CfgExpr::KeyValue(
+            key.to_string(),
+            value[1..value.len() - 1].to_string(),
+        ));
+    }
+
+    if !s
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err(cfg_parse_error(format!("invalid identifier `{s}`")));
+    }
+    Ok(CfgExpr::Ident(s.to_string()))
+}
+
+fn eval_cfg_expr(context: &Context, expr: &CfgExpr) -> bool {
+    match expr {
+        CfgExpr::Ident(name) => match name.as_str() {
+            "unix" => context.get_config(BuildConfigKey::TargetFamily) == Some("unix"),
+            "windows" => context.get_config(BuildConfigKey::TargetFamily) == Some("windows"),
+            _ => false,
+        },
+        CfgExpr::KeyValue(key, value) => {
+            let config_key = match key.as_str() {
+                "target_family" => BuildConfigKey::TargetFamily,
+                "target_os" => BuildConfigKey::TargetOS,
+                "target_env" => BuildConfigKey::TargetEnv,
+                // Unknown keys (e.g. target_arch, before it exists as a config key)
+                // are simply never satisfied.
+                _ => return false,
+            };
+            context.get_config(config_key) == Some(value.as_str())
+        }
+        CfgExpr::All(children) => children.iter().all(|c| eval_cfg_expr(context, c)),
+        CfgExpr::Any(children) => children.iter().any(|c| eval_cfg_expr(context, c)),
+        CfgExpr::Not(child) => !eval_cfg_expr(context, child),
+    }
+}
+
+pub(crate) fn resolve_cfg_directive(context: &Context, directive: &str) -> std::io::Result<bool> {
+    let expr = parse_cfg_expr(directive)?;
+    Ok(eval_cfg_expr(context, &expr))
 }
 
-// TODO: properly implement this (need to actually parse the cfg directive...)
-fn resolve_cfg_directive(context: &Context, directive: &str) -> std::io::Result<bool> {
-    if directive == "unix" && context.get_config(BuildConfigKey::TargetFamily) == Some("unix") {
-        return Ok(true);
+#[cfg(test)]
+mod cfg_tests {
+    use super::*;
+
+    fn context(values: &[(BuildConfigKey, &str)]) -> Context {
+        Context::new(
+            std::path::PathBuf::new(),
+            values
+                .iter()
+                .map(|(k, v)| (*k, v.to_string()))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn bare_identifier() {
+        let unix = context(&[(BuildConfigKey::TargetFamily, "unix")]);
+        assert!(resolve_cfg_directive(&unix, "unix").unwrap());
+        assert!(!resolve_cfg_directive(&unix, "windows").unwrap());
     }
-    Ok(false)
+
+    #[test]
+    fn key_value() {
+        let ctx = context(&[(BuildConfigKey::TargetOS, "linux")]);
+        assert!(resolve_cfg_directive(&ctx, "target_os = \"linux\"").unwrap());
+        assert!(!resolve_cfg_directive(&ctx, "target_os = \"macos\"").unwrap());
+    }
+
+    #[test]
+    fn all_any_not_combinators() {
+        let ctx = context(&[
+            (BuildConfigKey::TargetFamily, "unix"),
+            (BuildConfigKey::TargetOS, "linux"),
+        ]);
+        assert!(resolve_cfg_directive(&ctx, "all(unix, target_os = \"linux\")").unwrap());
+        assert!(!resolve_cfg_directive(&ctx, "all(unix, target_os = \"macos\")").unwrap());
+        assert!(resolve_cfg_directive(&ctx, "any(windows, target_os = \"linux\")").unwrap());
+        assert!(resolve_cfg_directive(&ctx, "not(windows)").unwrap());
+        assert!(
+            resolve_cfg_directive(&ctx, "all(unix, any(target_os = \"linux\", windows))")
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn unknown_key_is_never_satisfied() {
+        let ctx = context(&[]);
+        assert!(!resolve_cfg_directive(&ctx, "target_arch = \"x86_64\"").unwrap());
+    }
+
+    #[test]
+    fn rejects_malformed_expressions() {
+        assert!(parse_cfg_expr("").is_err());
+        assert!(parse_cfg_expr("not(unix, windows)").is_err());
+        assert!(parse_cfg_expr("target_os = linux").is_err());
+        assert!(parse_cfg_expr("all(unix").is_err());
+    }
+}
+
+/// Builds the download URL for a crate tarball from the configured
+/// registry's `dl` template (`BuildConfigKey::RegistryDlTemplate`), falling
+/// back to crates.io's own API when no alternate registry is configured.
+/// Supports the sparse-registry template markers `{crate}`, `{version}`,
+/// `{prefix}` and `{lowerprefix}`, and works equally well with a `file://`
+/// template for a local mirror, since downloads are performed with `curl`.
+fn registry_dl_url(context: &Context, crate_name: &str, crate_version: &str) -> String {
+    let template = context
+        .get_config(BuildConfigKey::RegistryDlTemplate)
+        .unwrap_or("https://crates.io/api/v1/crates/{crate}/{version}/download");
+    let prefix = crate::lockfile::sparse_index_dir(crate_name);
+
+    template
+        .replace("{crate}", crate_name)
+        .replace("{version}", crate_version)
+        .replace("{lowerprefix}", &prefix.to_lowercase())
+        .replace("{prefix}", &prefix)
+}
+
+/// Resolves the crate's source directory from a pre-extracted vendor
+/// directory (`BuildConfigKey::VendorPath`) when offline mode is active,
+/// instead of downloading and untarring it.
+fn vendored_crate_dir(
+    context: &Context,
+    crate_name: &str,
+    crate_version: &str,
+) -> std::io::Result<std::path::PathBuf> {
+    let vendor_path = context
+        .get_config(BuildConfigKey::VendorPath)
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "offline mode is enabled but no vendor path is configured",
+            )
+        })?;
+    let crate_dir =
+        std::path::Path::new(vendor_path).join(format!("{crate_name}-{crate_version}"));
+    if !crate_dir.exists() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!(
+                "offline mode: `{crate_name} {crate_version}` is not vendored at {} \
+                 (vendor it first, or disable offline mode)",
+                crate_dir.display()
+            ),
+        ));
+    }
+    Ok(crate_dir)
 }
 
 impl ResolverPlugin for CargoResolver {
@@ -168,10 +582,13 @@ impl ResolverPlugin for CargoResolver {
     }
 
     fn resolve(&self, context: Context, target: &str) -> std::io::Result<Config> {
-        let crate_name = target.strip_prefix("cargo://").ok_or(std::io::Error::new(
+        let name_and_bucket = target.strip_prefix("cargo://").ok_or(std::io::Error::new(
             std::io::ErrorKind::Other,
             "invalid target name",
         ))?;
+        // `target` is `cargo://name@bucket` - strip the bucket suffix back
+        // off to get the actual crate name (see `lockfile::dependency_target`).
+        let crate_name = name_and_bucket.split('@').next().unwrap_or(name_and_bucket);
 
         let lockstring = &context.get_locked_version(target)?;
         let (crate_version, features) = parse_lockstring(&lockstring);
@@ -179,36 +596,37 @@ impl ResolverPlugin for CargoResolver {
         let workdir = context.working_directory();
         std::fs::create_dir_all(&workdir).ok();
 
-        // Download the crate tarball
-        let tar_dest = workdir.join("crate.tar");
-
-        if !tar_dest.exists() {
-            context.actions.download(
-                &context,
-                format!(
-                    "https://crates.io/api/v1/crates/{}/{}/download",
-                    crate_name, crate_version
-                ),
-                &tar_dest,
-            )?;
-        }
+        let dest = if context.get_config(BuildConfigKey::Offline) == Some("true") {
+            vendored_crate_dir(&context, crate_name, crate_version)?
+        } else {
+            // Download the crate tarball
+            let tar_dest = workdir.join("crate.tar");
+            if !tar_dest.exists() {
+                context.actions.download(
+                    &context,
+                    registry_dl_url(&context, crate_name, crate_version),
+                    &tar_dest,
+                )?;
+            }
 
-        // Untar the crate tarball
-        let dest = workdir.join("crate");
-        if !dest.exists() {
-            std::fs::create_dir_all(&dest).ok();
-            context.actions.run_process(
-                &context,
-                "tar",
-                &[
-                    "xzvf",
-                    &tar_dest.to_string_lossy(),
-                    "-C",
-                    &dest.to_string_lossy(),
-                    "--strip-components=1",
-                ],
-            )?;
-        }
+            // Untar the crate tarball
+            let dest = workdir.join("crate");
+            if !dest.exists() {
+                std::fs::create_dir_all(&dest).ok();
+                context.actions.run_process(
+                    &context,
+                    "tar",
+                    &[
+                        "xzvf",
+                        &tar_dest.to_string_lossy(),
+                        "-C",
+                        &dest.to_string_lossy(),
+                        "--strip-components=1",
+                    ],
+                )?;
+            }
+            dest
+        };
 
         let mut rust_files = Vec::new();
         get_rust_files(&dest.join("src"), &mut rust_files)?;
@@ -222,8 +640,45 @@ impl ResolverPlugin for CargoResolver {
         let toml = parse_cargo_toml(&context, &dest.join("Cargo.toml"), &features)?;
 
         let mut deps = Vec::new();
-        for dep in toml.dependencies {
-            deps.push(format!("cargo://{dep}"));
+        for (dep_name, dep_req) in &toml.dependencies {
+            deps.push(crate::lockfile::dependency_target(dep_name, dep_req));
+        }
+
+        let build_dep_targets: Vec<String> = toml
+            .build_dependencies
+            .iter()
+            .map(|(dep_name, dep_req)| crate::lockfile::dependency_target(dep_name, dep_req))
+            .collect();
+        let mut build_dependencies = vec!["@rust_compiler".to_string()];
+        build_dependencies.extend(build_dep_targets.iter().cloned());
+
+        if let Some(build_script) = &toml.build_script {
+            let build_dep_rlibs: Vec<(String, std::path::PathBuf)> = build_dep_targets
+                .iter()
+                .map(|dep_target| self.resolve_and_compile_build_dependency(&context, dep_target))
+                .collect::<std::io::Result<Vec<_>>>()?;
+
+            let output = run_build_script(
+                &context,
+                &dest,
+                build_script,
+                crate_name,
+                crate_version,
+                &features,
+                &build_dep_rlibs,
+            )?;
+            if !output.cfgs.is_empty() {
+                extras.insert(ConfigExtraKeys::Cfgs, output.cfgs);
+            }
+            if !output.env.is_empty() {
+                extras.insert(ConfigExtraKeys::Env, output.env);
+            }
+            if !output.link_libs.is_empty() {
+                extras.insert(ConfigExtraKeys::LinkLibs, output.link_libs);
+            }
+            if !output.link_search_paths.is_empty() {
+                extras.insert(ConfigExtraKeys::LinkSearchPaths, output.link_search_paths);
+            }
         }
 
         Ok(Config {
@@ -234,10 +689,114 @@ impl ResolverPlugin for CargoResolver {
                 .into_iter()
                 .map(|s| s.to_string_lossy().to_string())
                 .collect(),
-            build_dependencies: vec!["@rust_compiler".to_string()],
+            build_dependencies,
             kind: PluginKind::RustLibrary.to_string(),
             extras,
             hash: 1010,
         })
     }
 }
+
+/// The `cargo:`/`cargo::` directives a `build.rs` printed on stdout that we
+/// know how to act on.
+#[derive(Debug, Default)]
+struct BuildScriptOutput {
+    cfgs: Vec<String>,
+    env: Vec<String>,
+    link_libs: Vec<String>,
+    link_search_paths: Vec<String>,
+}
+
+/// Compiles a crate's `build.rs` with `@rust_compiler`, runs it with the
+/// Cargo-documented environment, and returns the directives it emitted.
+///
+/// See <https://doc.rust-lang.org/cargo/reference/build-scripts.html>.
+fn run_build_script(
+    context: &Context,
+    crate_dir: &std::path::Path,
+    build_script: &str,
+    crate_name: &str,
+    crate_version: &str,
+    features: &[&str],
+    build_dep_rlibs: &[(String, std::path::PathBuf)],
+) -> std::io::Result<BuildScriptOutput> {
+    let workdir = context.working_directory();
+    let out_dir = workdir.join("build-script-out");
+    std::fs::create_dir_all(&out_dir).ok();
+
+    let script_src = crate_dir.join(build_script);
+    let script_bin = workdir.join("build-script-build");
+
+    let mut compile_args = vec![script_src.to_string_lossy().to_string()];
+    for (dep_name, rlib_path) in build_dep_rlibs {
+        compile_args.push("--extern".to_string());
+        compile_args.push(format!("{dep_name}={}", rlib_path.to_string_lossy()));
+    }
+    compile_args.push("-o".to_string());
+    compile_args.push(script_bin.to_string_lossy().to_string());
+    let compile_arg_refs: Vec<&str> = compile_args.iter().map(|s| s.as_str()).collect();
+    context
+        .actions
+        .run_process(context, "@rust_compiler", &compile_arg_refs)?;
+
+    let mut env_args = vec![
+        format!("OUT_DIR={}", out_dir.to_string_lossy()),
+        format!("CARGO_MANIFEST_DIR={}", crate_dir.to_string_lossy()),
+        format!("CARGO_PKG_NAME={crate_name}"),
+        format!("CARGO_PKG_VERSION={crate_version}"),
+        format!(
+            "CARGO_CFG_TARGET_OS={}",
+            context.get_config(BuildConfigKey::TargetOS).unwrap_or("")
+        ),
+        format!(
+            "CARGO_CFG_TARGET_FAMILY={}",
+            context
+                .get_config(BuildConfigKey::TargetFamily)
+                .unwrap_or("")
+        ),
+        format!(
+            "CARGO_CFG_TARGET_ENV={}",
+            context.get_config(BuildConfigKey::TargetEnv).unwrap_or("")
+        ),
+    ];
+    for feature in features {
+        env_args.push(format!(
+            "CARGO_FEATURE_{}=1",
+            feature.to_uppercase().replace('-', "_")
+        ));
+    }
+    env_args.push(script_bin.to_string_lossy().to_string());
+
+    let env_arg_refs: Vec<&str> = env_args.iter().map(|s| s.as_str()).collect();
+    let stdout = context.actions.run_process(context, "env", &env_arg_refs)?;
+
+    Ok(parse_build_script_output(&stdout))
+}
+
+fn parse_build_script_output(stdout: &str) -> BuildScriptOutput {
+    let mut output = BuildScriptOutput::default();
+
+    for line in stdout.lines() {
+        let Some(rest) = line
+            .strip_prefix("cargo::")
+            .or_else(|| line.strip_prefix("cargo:"))
+        else {
+            continue;
+        };
+        let Some((key, value)) = rest.split_once('=') else {
+            continue;
+        };
+
+        match key {
+            "rustc-cfg" => output.cfgs.push(value.to_string()),
+            "rustc-env" => output.env.push(value.to_string()),
+            "rustc-link-lib" => output.link_libs.push(value.to_string()),
+            "rustc-link-search" => output.link_search_paths.push(value.to_string()),
+            // `rerun-if-changed`/`rerun-if-env-changed` only affect whether the
+            // build script re-runs on a future build; nothing to propagate.
+            _ => {}
+        }
+    }
+
+    output
+}
@@ -42,6 +42,24 @@ impl Context {
         self.hash
     }
 
+    /// Builds a `Context` whose lockfile is already populated by resolving
+    /// `roots` (each a `cargo://name@req` target plus its directly requested
+    /// features) against the configured registry - the real entry point for
+    /// `lockfile::resolve_lockfile`, so `get_locked_version` resolves as
+    /// `CargoResolver::resolve` expects.
+    pub fn with_resolved_lockfile<T: IntoIterator<Item = (BuildConfigKey, String)>>(
+        cache_dir: std::path::PathBuf,
+        config: T,
+        roots: &[(String, Vec<String>)],
+    ) -> std::io::Result<Self> {
+        let context = Self::new(cache_dir, config);
+        let lockfile = crate::lockfile::resolve_lockfile(&context, roots)?;
+        Ok(Self {
+            lockfile: Arc::new(lockfile),
+            ..context
+        })
+    }
+
     pub fn with_target(&self, target: &str) -> Self {
         let mut s = self.clone();
         s.target = Some(target.to_string());
@@ -21,10 +21,97 @@ pub enum BuildConfigKey {
     TargetFamily = 1,
     TargetEnv,
     TargetOS,
+    /// Base URL of the sparse registry index, e.g. `https://index.crates.io`
+    /// or a private mirror. Defaults to crates.io's own index.
+    RegistryIndexUrl,
+    /// The registry's download URL template, following the sparse-registry
+    /// `dl` config field convention: `{crate}`, `{version}`, `{prefix}` and
+    /// `{lowerprefix}` are substituted in. Defaults to crates.io's API.
+    RegistryDlTemplate,
+    /// Path to a local, pre-extracted vendor directory (crates laid out as
+    /// `{name}-{version}/`), consulted instead of the network when
+    /// `Offline` is set.
+    VendorPath,
+    /// `"true"` to forbid network downloads entirely and resolve crates
+    /// from `VendorPath` instead.
+    Offline,
 }
 
 #[derive(Debug, Clone)]
-pub struct BuildActions {}
+pub struct BuildActions {
+    backend: Arc<dyn crate::sandbox::ExecutionBackend>,
+    jobserver: Option<Arc<crate::scheduler::JobServer>>,
+}
+
+#[cfg(target_os = "linux")]
+fn default_execution_backend() -> Arc<dyn crate::sandbox::ExecutionBackend> {
+    Arc::new(crate::sandbox::NamespaceSandbox::default())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn default_execution_backend() -> Arc<dyn crate::sandbox::ExecutionBackend> {
+    Arc::new(crate::sandbox::NoSandbox::default())
+}
+
+/// Sizes the default jobserver to the number of available CPUs, same as
+/// `cargo` itself does when nothing overrides `--jobs`.
+fn default_parallelism() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
+impl BuildActions {
+    pub fn new() -> Self {
+        let jobserver = crate::scheduler::JobServer::new(default_parallelism())
+            .map(Arc::new)
+            .ok();
+        Self {
+            backend: default_execution_backend(),
+            jobserver,
+        }
+    }
+
+    /// Overrides the execution backend, e.g. to force `NoSandbox` in tests
+    /// or on a platform that can't unshare namespaces.
+    pub fn with_backend(backend: Arc<dyn crate::sandbox::ExecutionBackend>) -> Self {
+        Self {
+            backend,
+            jobserver: None,
+        }
+    }
+
+    /// Attaches a jobserver so every `run_process` call passes its
+    /// `MAKEFLAGS`/`CARGO_MAKEFLAGS` through, letting child `rustc`/`cc`
+    /// invocations draw from the same token pool as the scheduler.
+    pub fn with_jobserver(mut self, jobserver: Arc<crate::scheduler::JobServer>) -> Self {
+        self.jobserver = Some(jobserver);
+        self
+    }
+
+    pub fn run_process(
+        &self,
+        context: &Context,
+        command: &str,
+        args: &[&str],
+    ) -> std::io::Result<String> {
+        let extra_env = self
+            .jobserver
+            .as_ref()
+            .map(|js| js.env_vars())
+            .unwrap_or_default();
+        self.backend.run(context, command, args, &extra_env)
+    }
+
+    pub fn download(
+        &self,
+        context: &Context,
+        url: String,
+        dest: &std::path::Path,
+    ) -> std::io::Result<()> {
+        crate::sandbox::download_direct(context, &url, dest)
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Task {
@@ -45,6 +132,17 @@ pub enum BuildResult {
 
 pub mod BuildOutputKind {
     pub const TransitiveProducts: u32 = 0;
+    /// Combined `rustc-link-lib`/`rustc-link-search` flags (as `-l foo` /
+    /// `-L path` strings) this crate's build script emitted, plus whatever
+    /// its own dependencies already carried in their own `LinkFlags` -
+    /// `BuildResult::merged` folds a dependency's entries forward, so a
+    /// downstream binary-linking `Task` only has to read its *direct*
+    /// dependencies' `BuildOutput`s to pick up the whole transitive set. A
+    /// build plugin for a crate with a build script is expected to turn the
+    /// bare directive values in `Config::get(ConfigExtraKeys::LinkLibs)` /
+    /// `ConfigExtraKeys::LinkSearchPaths` into `-l`/`-L` flags here when it
+    /// builds.
+    pub const LinkFlags: u32 = 1;
 }
 
 #[derive(Debug, Default, Clone, PartialEq)]
@@ -69,17 +167,24 @@ impl BuildResult {
 
     pub fn merged<'a, I: Iterator<Item = &'a Self>>(results: I) -> Self {
         let mut outs = Vec::new();
+        let mut extras: HashMap<u32, Vec<String>> = HashMap::new();
         for result in results {
             match result {
-                BuildResult::Success(BuildOutput { outputs, extras: _ }) => {
+                BuildResult::Success(BuildOutput {
+                    outputs,
+                    extras: result_extras,
+                }) => {
                     outs.extend(outputs.to_owned());
+                    for (k, v) in result_extras {
+                        extras.entry(*k).or_default().extend(v.iter().cloned());
+                    }
                 }
                 _ => return result.clone(),
             }
         }
         BuildResult::Success(BuildOutput {
             outputs: outs,
-            ..Default::default()
+            extras,
         })
     }
 }
@@ -98,6 +203,20 @@ pub struct Config {
 
 pub mod ConfigExtraKeys {
     pub const Features: u32 = 0;
+    /// `rustc-cfg` directives emitted by `build.rs`, passed through to this
+    /// crate's own compilation as `--cfg` flags (consumed by the external
+    /// rust-library build plugin, same hand-off as `Env`/`LinkLibs` below).
+    /// These do not feed back into `resolve_cfg_directive`'s evaluation of
+    /// `[target.'cfg(...)'.dependencies]` tables - real Cargo doesn't do
+    /// that either: target-gated dependencies are resolved once, up front,
+    /// purely from the target triple, before a build script ever runs.
+    pub const Cfgs: u32 = 1;
+    /// `rustc-env` directives emitted by `build.rs`.
+    pub const Env: u32 = 2;
+    /// `rustc-link-lib` directives emitted by `build.rs`.
+    pub const LinkLibs: u32 = 3;
+    /// `rustc-link-search` directives emitted by `build.rs`.
+    pub const LinkSearchPaths: u32 = 4;
 }
 
 impl Config {
@@ -180,6 +299,20 @@ impl Task {
         return TaskStatus::Building;
     }
 
+    /// Builds this task under `jobserver`, blocking the calling worker until
+    /// a token is free before invoking `build`, then records the result.
+    /// This is the integration point a scheduler loop drives `Task`s
+    /// through to get jobserver-bounded concurrency; see
+    /// `scheduler::build_task`.
+    pub fn build(
+        &mut self,
+        jobserver: &crate::scheduler::JobServer,
+        build: impl FnOnce() -> BuildResult,
+    ) -> std::io::Result<()> {
+        self.result = Some(crate::scheduler::build_task(jobserver, self, build)?);
+        Ok(())
+    }
+
     pub fn status(&self) -> TaskStatus {
         if self.result.is_some() {
             return TaskStatus::Done;
@@ -0,0 +1,200 @@
+use std::collections::{HashMap, HashSet};
+
+/// What turning on a single `[features]` entry implies, following Cargo's own
+/// feature value syntax.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum FeatureEdge {
+    /// `foo` - enables another feature (or optional dependency of the same
+    /// name) on this crate.
+    Feature(String),
+    /// `dep:foo` - turns on an optional dependency without enabling any of
+    /// its features.
+    Dependency(String),
+    /// `foo/bar` (or the weak `foo?/bar`) - enables feature `bar` on
+    /// dependency `foo`. A non-weak edge also turns `foo` on, exactly like a
+    /// bare `foo` or `dep:foo` edge would; a weak edge only applies `bar`
+    /// once something else has already turned `foo` on as an optional
+    /// dependency of the crate declaring this edge.
+    DependencyFeature {
+        dep: String,
+        feature: String,
+        weak: bool,
+    },
+}
+
+fn parse_feature_edge(raw: &str) -> FeatureEdge {
+    if let Some(dep) = raw.strip_prefix("dep:") {
+        return FeatureEdge::Dependency(dep.to_string());
+    }
+    if let Some((dep, feature)) = raw.split_once('/') {
+        let weak = dep.ends_with('?');
+        let dep = dep.strip_suffix('?').unwrap_or(dep);
+        return FeatureEdge::DependencyFeature {
+            dep: dep.to_string(),
+            feature: feature.to_string(),
+            weak,
+        };
+    }
+    FeatureEdge::Feature(raw.to_string())
+}
+
+/// Computes the transitive fixpoint of enabled features across a resolved
+/// dependency graph.
+///
+/// `feature_tables` maps each crate to its `[features]` table (feature name
+/// -> the raw implication strings Cargo would write there). `initial` maps
+/// each crate to the set of features requested directly by its dependents
+/// (including `dep/feat` cross-crate activations already folded in by the
+/// caller). Propagation repeats until no crate gains a new feature.
+pub fn unify_features(
+    feature_tables: &HashMap<String, HashMap<String, Vec<String>>>,
+    initial: &HashMap<String, HashSet<String>>,
+) -> HashMap<String, HashSet<String>> {
+    let mut enabled = initial.clone();
+
+    loop {
+        let mut changed = false;
+
+        // Snapshot the crates we know about so we can mutate `enabled` while
+        // iterating over what was true at the start of this round.
+        let crates: Vec<String> = enabled.keys().cloned().collect();
+        for krate in crates {
+            let features_for_crate: Vec<String> = enabled
+                .get(&krate)
+                .map(|f| f.iter().cloned().collect())
+                .unwrap_or_default();
+
+            for feature in features_for_crate {
+                let edges = match feature_tables.get(&krate).and_then(|t| t.get(&feature)) {
+                    Some(edges) => edges,
+                    // Not a named `[features]` entry (e.g. it's a bare optional
+                    // dependency name) - nothing further to propagate.
+                    None => continue,
+                };
+
+                for raw in edges {
+                    match parse_feature_edge(raw) {
+                        FeatureEdge::Feature(f) => {
+                            if enabled.entry(krate.clone()).or_default().insert(f) {
+                                changed = true;
+                            }
+                        }
+                        // `dep:foo` activates the optional dependency `foo`
+                        // of *this* crate (`krate`), same namespace a bare
+                        // `foo` edge would use - it just doesn't also expose
+                        // an implicit feature named `foo`.
+                        FeatureEdge::Dependency(dep) => {
+                            if enabled.entry(krate.clone()).or_default().insert(dep) {
+                                changed = true;
+                            }
+                        }
+                        FeatureEdge::DependencyFeature { dep, feature, weak } => {
+                            // A weak edge only applies `feature` once
+                            // something else has already turned `dep` on as
+                            // an optional dependency of `krate` - it never
+                            // turns `dep` on by itself. Since this whole loop
+                            // reruns every round until fixpoint, a weak edge
+                            // that can't fire yet just gets re-checked next
+                            // round once something else enables `dep`.
+                            let dep_already_on = enabled
+                                .get(&krate)
+                                .map(|feats| feats.contains(&dep))
+                                .unwrap_or(false);
+                            if (!weak || dep_already_on)
+                                && enabled.entry(dep.clone()).or_default().insert(feature)
+                            {
+                                changed = true;
+                            }
+                            if !weak
+                                && enabled.entry(krate.clone()).or_default().insert(dep)
+                            {
+                                changed = true;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        if !changed {
+            break;
+        }
+    }
+
+    enabled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(entries: &[(&str, &[&str])]) -> HashMap<String, Vec<String>> {
+        entries
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.iter().map(|s| s.to_string()).collect()))
+            .collect()
+    }
+
+    fn set(entries: &[&str]) -> HashSet<String> {
+        entries.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn dep_colon_activates_the_optional_dependency() {
+        let feature_tables = HashMap::from([("a".to_string(), table(&[("x", &["dep:foo"])]))]);
+        let initial = HashMap::from([("a".to_string(), set(&["x"]))]);
+
+        let enabled = unify_features(&feature_tables, &initial);
+
+        assert!(enabled["a"].contains("foo"));
+    }
+
+    #[test]
+    fn non_weak_slash_edge_also_activates_the_dependency() {
+        let feature_tables = HashMap::from([("a".to_string(), table(&[("x", &["foo/bar"])]))]);
+        let initial = HashMap::from([("a".to_string(), set(&["x"]))]);
+
+        let enabled = unify_features(&feature_tables, &initial);
+
+        assert!(enabled["foo"].contains("bar"));
+        assert!(enabled["a"].contains("foo"));
+    }
+
+    #[test]
+    fn weak_slash_edge_does_not_activate_the_dependency() {
+        let feature_tables = HashMap::from([("a".to_string(), table(&[("x", &["foo?/bar"])]))]);
+        let initial = HashMap::from([("a".to_string(), set(&["x"]))]);
+
+        let enabled = unify_features(&feature_tables, &initial);
+
+        assert!(!enabled.get("foo").map(|f| f.contains("bar")).unwrap_or(false));
+        assert!(!enabled.get("a").map(|f| f.contains("foo")).unwrap_or(false));
+    }
+
+    #[test]
+    fn weak_slash_edge_applies_once_the_dependency_is_otherwise_enabled() {
+        let feature_tables = HashMap::from([(
+            "a".to_string(),
+            table(&[("x", &["dep:foo", "foo?/bar"])]),
+        )]);
+        let initial = HashMap::from([("a".to_string(), set(&["x"]))]);
+
+        let enabled = unify_features(&feature_tables, &initial);
+
+        assert!(enabled["foo"].contains("bar"));
+    }
+
+    #[test]
+    fn bare_feature_propagates_transitively() {
+        let feature_tables = HashMap::from([(
+            "a".to_string(),
+            table(&[("default", &["extra"]), ("extra", &["foo/bar"])]),
+        )]);
+        let initial = HashMap::from([("a".to_string(), set(&["default"]))]);
+
+        let enabled = unify_features(&feature_tables, &initial);
+
+        assert!(enabled["a"].contains("extra"));
+        assert!(enabled["foo"].contains("bar"));
+    }
+}
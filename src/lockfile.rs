@@ -0,0 +1,566 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::cargo::resolve_cfg_directive;
+use crate::core::{BuildConfigKey, Context};
+use crate::features::unify_features;
+
+fn default_true() -> bool {
+    true
+}
+
+/// One published version of a crate, as served by the crates.io sparse index
+/// (`https://index.crates.io/{prefix}/{name}`, newline-delimited JSON).
+#[derive(Debug, Clone, serde::Deserialize)]
+struct IndexVersion {
+    vers: String,
+    #[serde(default)]
+    deps: Vec<IndexDep>,
+    #[serde(default)]
+    features: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    yanked: bool,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+struct IndexDep {
+    name: String,
+    req: String,
+    #[serde(default)]
+    features: Vec<String>,
+    #[serde(default = "default_true")]
+    default_features: bool,
+    #[serde(default)]
+    optional: bool,
+    #[serde(default)]
+    kind: Option<String>,
+    #[serde(default)]
+    target: Option<String>,
+}
+
+/// Computes the sparse-index shard directory for `name`, following
+/// crates.io's sharding scheme: 1 and 2 char names live directly under
+/// `1/`/`2/`, 3 char names get an extra `3/{first-char}/` level, and
+/// everything else is sharded by its first four characters.
+pub(crate) fn sparse_index_dir(name: &str) -> String {
+    let lower = name.to_lowercase();
+    match lower.len() {
+        1 => "1".to_string(),
+        2 => "2".to_string(),
+        3 => format!("3/{}", &lower[0..1]),
+        _ => format!("{}/{}", &lower[0..2], &lower[2..4]),
+    }
+}
+
+fn sparse_index_path(name: &str) -> String {
+    format!("{}/{}", sparse_index_dir(name), name.to_lowercase())
+}
+
+fn sparse_index_url(context: &Context, name: &str) -> String {
+    let base = context
+        .get_config(BuildConfigKey::RegistryIndexUrl)
+        .unwrap_or("https://index.crates.io")
+        .trim_end_matches('/');
+    format!("{base}/{}", sparse_index_path(name))
+}
+
+fn fetch_index_versions(context: &Context, name: &str) -> std::io::Result<Vec<IndexVersion>> {
+    let dest = context
+        .cache_dir
+        .join("index")
+        .join(format!("{}.json", name.to_lowercase()));
+
+    if !dest.exists() {
+        // Never hit the network in offline mode, even if nothing was cached
+        // from a previous run - fail with a clear, actionable error (or,
+        // better, serve the vendor directory) instead of letting the
+        // download itself fail with a raw curl error.
+        if context.get_config(BuildConfigKey::Offline) == Some("true") {
+            return vendored_index_versions(context, name);
+        }
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent).ok();
+        }
+        context
+            .actions
+            .download(context, sparse_index_url(context, name), &dest)?;
+    }
+
+    let content = std::fs::read_to_string(&dest)?;
+    let mut versions = Vec::new();
+    for line in content.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let version: IndexVersion = serde_json::from_str(line)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        if version.yanked {
+            continue;
+        }
+        versions.push(version);
+    }
+    Ok(versions)
+}
+
+/// Reconstructs the subset of index data we need (available versions, their
+/// deps and `[features]` table) straight from a vendor directory's own
+/// `Cargo.toml` files, since offline mode has no sparse-index JSON to read -
+/// `VendorPath` holds `{name}-{version}/` directories, same layout
+/// `vendored_crate_dir` expects.
+fn vendored_index_versions(context: &Context, name: &str) -> std::io::Result<Vec<IndexVersion>> {
+    let vendor_path = context.get_config(BuildConfigKey::VendorPath).ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!(
+                "offline mode is enabled but no vendor path is configured \
+                 (needed to resolve `{name}`)"
+            ),
+        )
+    })?;
+
+    let prefix = format!("{name}-");
+    let mut versions = Vec::new();
+    let read_dir = std::fs::read_dir(vendor_path).map_err(|e| {
+        std::io::Error::new(
+            e.kind(),
+            format!("offline mode: could not read vendor directory {vendor_path}: {e}"),
+        )
+    })?;
+    for entry in read_dir {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let Some(version_str) = file_name.to_string_lossy().strip_prefix(&prefix).map(str::to_string) else {
+            continue;
+        };
+        if semver::Version::parse(&version_str).is_err() {
+            continue;
+        }
+        let manifest = entry.path().join("Cargo.toml");
+        if !manifest.exists() {
+            continue;
+        }
+        versions.push(parse_vendored_index_version(&manifest, &version_str)?);
+    }
+
+    if versions.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!(
+                "offline mode: no vendored copies of `{name}` found under {vendor_path} \
+                 (vendor it first, or disable offline mode)"
+            ),
+        ));
+    }
+    Ok(versions)
+}
+
+/// Parses a vendored crate's own `Cargo.toml` into an `IndexVersion`,
+/// reading just the bits `LockResolver` needs: `[dependencies]` (plus
+/// `[target.'cfg(...)'.dependencies]`) and `[features]`.
+fn parse_vendored_index_version(
+    manifest: &std::path::Path,
+    version: &str,
+) -> std::io::Result<IndexVersion> {
+    let content = std::fs::read_to_string(manifest)?;
+    let table = content
+        .parse::<toml::Table>()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+
+    let mut features = HashMap::new();
+    if let Some(toml::Value::Table(t)) = table.get("features") {
+        for (k, v) in t {
+            if let toml::Value::Array(arr) = v {
+                features.insert(
+                    k.clone(),
+                    arr.iter()
+                        .filter_map(|x| x.as_str().map(str::to_string))
+                        .collect(),
+                );
+            }
+        }
+    }
+
+    let mut deps = Vec::new();
+    if let Some(toml::Value::Table(t)) = table.get("dependencies") {
+        for (k, v) in t {
+            deps.push(vendored_index_dep(k, v, None));
+        }
+    }
+    if let Some(toml::Value::Table(targets)) = table.get("target") {
+        for (target_key, target_table) in targets {
+            if let Some(toml::Value::Table(dep_table)) = target_table.get("dependencies") {
+                for (k, v) in dep_table {
+                    deps.push(vendored_index_dep(k, v, Some(target_key.clone())));
+                }
+            }
+        }
+    }
+
+    Ok(IndexVersion {
+        vers: version.to_string(),
+        deps,
+        features,
+        yanked: false,
+    })
+}
+
+fn vendored_index_dep(name: &str, v: &toml::Value, target: Option<String>) -> IndexDep {
+    let (req, optional, default_features, dep_features) = match v {
+        toml::Value::String(s) => (s.clone(), false, true, Vec::new()),
+        toml::Value::Table(t) => {
+            let req = match t.get("version") {
+                Some(toml::Value::String(s)) => s.clone(),
+                _ => "*".to_string(),
+            };
+            let optional = matches!(t.get("optional"), Some(toml::Value::Boolean(true)));
+            let default_features =
+                !matches!(t.get("default-features"), Some(toml::Value::Boolean(false)));
+            let dep_features = match t.get("features") {
+                Some(toml::Value::Array(arr)) => arr
+                    .iter()
+                    .filter_map(|x| x.as_str().map(str::to_string))
+                    .collect(),
+                _ => Vec::new(),
+            };
+            (req, optional, default_features, dep_features)
+        }
+        _ => ("*".to_string(), false, true, Vec::new()),
+    };
+    IndexDep {
+        name: name.to_string(),
+        req,
+        features: dep_features,
+        default_features,
+        optional,
+        kind: None,
+        target,
+    }
+}
+
+/// Approximates Cargo's semver-compatibility partitioning from a
+/// requirement's first comparator: two requirements land in the same
+/// "compatible range" - and so must resolve to the same pinned version -
+/// exactly when Cargo's caret rules would treat their lower bounds as
+/// interchangeable (same major, or same `0.minor`, or same `0.0.patch`).
+/// Requirements with more than one comparator (e.g. `>=1.2, <1.5`) are
+/// bucketed by their first comparator only - a simplification, not a full
+/// reimplementation of Cargo's resolver, but enough to let two genuinely
+/// incompatible majors of the same crate name (e.g. `^1` and `^2`) resolve
+/// to two separately-pinned versions instead of erroring out.
+pub(crate) fn compatible_range_key(req: &semver::VersionReq) -> String {
+    let Some(comparator) = req.comparators.first() else {
+        return "any".to_string();
+    };
+    if comparator.major > 0 {
+        return comparator.major.to_string();
+    }
+    match comparator.minor {
+        Some(minor) if minor > 0 => format!("0.{minor}"),
+        Some(_) => format!("0.0.{}", comparator.patch.unwrap_or(0)),
+        None => "0".to_string(),
+    }
+}
+
+/// The `cargo://` target for a dependency named `name` with requirement
+/// `req_str`, qualified by its compatible-range bucket so that two
+/// incompatible majors of the same crate get distinct targets. Falls back to
+/// bucket `"any"` if `req_str` doesn't parse as a semver requirement (e.g. a
+/// git/path dependency with no declared version).
+pub(crate) fn dependency_target(name: &str, req_str: &str) -> String {
+    let bucket = semver::VersionReq::parse(req_str)
+        .ok()
+        .map(|req| compatible_range_key(&req))
+        .unwrap_or_else(|| "any".to_string());
+    format!("cargo://{name}@{bucket}")
+}
+
+/// Identifies one resolved node in the dependency graph: a crate name plus
+/// the compatible-range bucket it was resolved within. Kept as a single
+/// `name@bucket` string (rather than a tuple) so it doubles as the
+/// `cargo://` target suffix `into_lockfile` emits.
+fn crate_id(name: &str, bucket: &str) -> String {
+    format!("{name}@{bucket}")
+}
+
+fn parse_cargo_target(target: &str) -> std::io::Result<(String, String)> {
+    let rest = target.strip_prefix("cargo://").ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("`{target}` is not a cargo:// target"),
+        )
+    })?;
+    match rest.split_once('@') {
+        Some((name, req)) => Ok((name.to_string(), req.to_string())),
+        None => Ok((rest.to_string(), "*".to_string())),
+    }
+}
+
+/// Resolves a set of root `(cargo://name@req, requested features)` targets
+/// (and their transitive dependencies, including optional ones unlocked by
+/// feature unification) into the `{target -> "version,feat1,feat2"}` map
+/// consumed by `Context::get_locked_version`, with targets keyed as
+/// `cargo://name@bucket` (see `compatible_range_key`) to match what
+/// `cargo.rs` requests dependencies by.
+///
+/// A single version is chosen per `(name, compatible range)` pair: every
+/// requirement that reaches a given crate *within the same bucket* must be
+/// satisfied by the same pinned version, which is always the highest
+/// non-yanked version satisfying all of them - but two incompatible majors
+/// of the same crate name (e.g. `^1` and `^2`) land in different buckets and
+/// so resolve to two coexisting pinned versions, same as real Cargo. Feature
+/// unification is still tracked per bare crate name rather than per bucket:
+/// when two buckets of the same name coexist (e.g. one dependent asks for
+/// `^1` and another for `^2`), feature requests are unified across all of
+/// them rather than kept separate, and every pinned version of that name is
+/// emitted with the same feature list.
+pub fn resolve_lockfile(
+    context: &Context,
+    roots: &[(String, Vec<String>)],
+) -> std::io::Result<HashMap<String, String>> {
+    let mut resolver = LockResolver::new(context);
+    for (root, requested_features) in roots {
+        let (name, req) = parse_cargo_target(root)?;
+        let entry = resolver.initial_features.entry(name.clone()).or_default();
+        entry.insert("default".to_string());
+        entry.extend(requested_features.iter().cloned());
+        resolver.resolve_crate(&name, &req)?;
+    }
+    resolver.unify_and_expand()?;
+    Ok(resolver.into_lockfile())
+}
+
+struct LockResolver<'a> {
+    context: &'a Context,
+    requirements: HashMap<String, Vec<semver::VersionReq>>,
+    versions: HashMap<String, semver::Version>,
+    in_progress: HashSet<String>,
+
+    // Feature-unification bookkeeping, see `unify_and_expand`.
+    feature_tables: HashMap<String, HashMap<String, Vec<String>>>,
+    initial_features: HashMap<String, HashSet<String>>,
+    optional_deps: HashMap<String, HashSet<String>>,
+    pending_optional: Vec<IndexDep>,
+    activated_optional: HashSet<String>,
+    unified_features: HashMap<String, HashSet<String>>,
+}
+
+impl<'a> LockResolver<'a> {
+    fn new(context: &'a Context) -> Self {
+        Self {
+            context,
+            requirements: HashMap::new(),
+            versions: HashMap::new(),
+            in_progress: HashSet::new(),
+            feature_tables: HashMap::new(),
+            initial_features: HashMap::new(),
+            optional_deps: HashMap::new(),
+            pending_optional: Vec::new(),
+            activated_optional: HashSet::new(),
+            unified_features: HashMap::new(),
+        }
+    }
+
+    fn resolve_crate(&mut self, name: &str, req_str: &str) -> std::io::Result<()> {
+        let req = semver::VersionReq::parse(req_str)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        let bucket = compatible_range_key(&req);
+        let id = crate_id(name, &bucket);
+
+        if let Some(existing) = self.versions.get(&id) {
+            if req.matches(existing) {
+                return Ok(());
+            }
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "version conflict for `{name}`: already locked to {existing} \
+                     in the `{bucket}` compatible range, which does not satisfy `{req_str}`"
+                ),
+            ));
+        }
+
+        if !self.in_progress.insert(id.clone()) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("dependency cycle detected while resolving `{name}`"),
+            ));
+        }
+
+        let candidates = fetch_index_versions(self.context, name)?;
+        let accumulated = self.requirements.entry(id.clone()).or_default();
+        accumulated.push(req.clone());
+
+        let best = candidates
+            .into_iter()
+            .filter_map(|v| semver::Version::parse(&v.vers).ok().map(|version| (version, v)))
+            .filter(|(version, _)| accumulated.iter().all(|r| r.matches(version)))
+            .max_by(|(a, _), (b, _)| a.cmp(b))
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("no version of `{name}` satisfies `{req_str}`"),
+                )
+            })?;
+        let (version, index_version) = best;
+        // Feature bookkeeping stays keyed by bare name - see the
+        // known-simplification note on `resolve_lockfile`.
+        self.feature_tables
+            .insert(name.to_string(), index_version.features);
+
+        // `id` is only marked resolved (in `self.versions`) *after* its own
+        // deps are done, so that a dependency cycle re-entering `id` here
+        // still finds `in_progress` holding it and reports the error above,
+        // instead of hitting the already-resolved short-circuit and silently
+        // no-op'ing the cycle away.
+        for dep in index_version.deps {
+            // Dev-dependencies never affect the build of a dependent crate.
+            if dep.kind.as_deref() == Some("dev") {
+                continue;
+            }
+
+            let features = self.initial_features.entry(dep.name.clone()).or_default();
+            features.extend(dep.features.iter().cloned());
+            if dep.default_features {
+                features.insert("default".to_string());
+            }
+
+            if dep.optional {
+                self.optional_deps
+                    .entry(name.to_string())
+                    .or_default()
+                    .insert(dep.name.clone());
+                self.pending_optional.push(dep);
+                continue;
+            }
+
+            if !self.cfg_matches(&dep)? {
+                continue;
+            }
+            self.resolve_crate(&dep.name, &dep.req)?;
+        }
+
+        self.versions.insert(id.clone(), version);
+        self.in_progress.remove(&id);
+        Ok(())
+    }
+
+    fn cfg_matches(&self, dep: &IndexDep) -> std::io::Result<bool> {
+        let target = match &dep.target {
+            Some(target) => target,
+            None => return Ok(true),
+        };
+        match target.strip_prefix("cfg(").and_then(|s| s.strip_suffix(')')) {
+            Some(directive) => resolve_cfg_directive(self.context, directive),
+            // A raw target triple: we have no way to evaluate it against the
+            // coarse family/os/env config keys we track, so we conservatively
+            // exclude it rather than guess.
+            None => Ok(false),
+        }
+    }
+
+    /// Repeatedly unifies features across the graph resolved so far and pins
+    /// any optional dependency that unification turns on, until a round adds
+    /// nothing new.
+    fn unify_and_expand(&mut self) -> std::io::Result<()> {
+        loop {
+            let unified = unify_features(&self.feature_tables, &self.initial_features);
+
+            let mut newly_activated = Vec::new();
+            for (owner, opt_deps) in &self.optional_deps {
+                let Some(enabled) = unified.get(owner) else {
+                    continue;
+                };
+                for dep_name in opt_deps {
+                    if enabled.contains(dep_name) && self.activated_optional.insert(dep_name.clone())
+                    {
+                        newly_activated.push(dep_name.clone());
+                    }
+                }
+            }
+
+            self.unified_features = unified;
+            if newly_activated.is_empty() {
+                break;
+            }
+
+            for dep_name in newly_activated {
+                let edges: Vec<IndexDep> = self
+                    .pending_optional
+                    .iter()
+                    .filter(|d| d.name == dep_name)
+                    .cloned()
+                    .collect();
+                for dep in edges {
+                    // No separate "already resolved" guard needed here:
+                    // once the dep's (name, bucket) pair is already pinned,
+                    // `resolve_crate` is a no-op if the pinned version
+                    // satisfies this requirement and a version-conflict
+                    // error otherwise.
+                    if !self.cfg_matches(&dep)? {
+                        continue;
+                    }
+                    self.resolve_crate(&dep.name, &dep.req)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn into_lockfile(self) -> HashMap<String, String> {
+        self.versions
+            .into_iter()
+            .map(|(id, version)| {
+                let name = id.split('@').next().unwrap_or(&id);
+                let mut entry = version.to_string();
+                if let Some(feats) = self.unified_features.get(name) {
+                    let mut feats: Vec<_> = feats
+                        .iter()
+                        .filter(|f| f.as_str() != "default")
+                        .cloned()
+                        .collect();
+                    feats.sort();
+                    for feat in feats {
+                        entry.push(',');
+                        entry.push_str(&feat);
+                    }
+                }
+                (format!("cargo://{id}"), entry)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bucket(req: &str) -> String {
+        compatible_range_key(&semver::VersionReq::parse(req).unwrap())
+    }
+
+    #[test]
+    fn incompatible_majors_land_in_different_buckets() {
+        assert_ne!(bucket("^1"), bucket("^2"));
+        assert_eq!(bucket("^1.2"), bucket("^1.9"));
+    }
+
+    #[test]
+    fn zero_dot_x_minors_are_each_their_own_bucket() {
+        assert_ne!(bucket("^0.3"), bucket("^0.4"));
+        assert_eq!(bucket("^0.3.1"), bucket("^0.3.9"));
+    }
+
+    #[test]
+    fn zero_dot_zero_patches_are_each_their_own_bucket() {
+        assert_ne!(bucket("^0.0.1"), bucket("^0.0.2"));
+    }
+
+    #[test]
+    fn dependency_target_is_bucket_qualified() {
+        assert_eq!(dependency_target("serde", "^1.2"), "cargo://serde@1");
+        assert_eq!(dependency_target("serde", "^2.0"), "cargo://serde@2");
+        assert_ne!(
+            dependency_target("serde", "^1"),
+            dependency_target("serde", "^2")
+        );
+    }
+}
@@ -0,0 +1,234 @@
+use std::process::Stdio;
+
+use crate::core::Context;
+
+/// Returned when a build action's command exits non-zero, carrying its
+/// captured stderr so callers (and their users) don't have to re-run the
+/// command by hand to see what went wrong.
+#[derive(Debug)]
+pub struct ProcessError {
+    pub command: String,
+    pub status: Option<i32>,
+    pub stderr: String,
+}
+
+impl std::fmt::Display for ProcessError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "command `{}` failed (exit status {:?}): {}",
+            self.command, self.status, self.stderr
+        )
+    }
+}
+
+impl std::error::Error for ProcessError {}
+
+impl From<ProcessError> for std::io::Error {
+    fn from(e: ProcessError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::Other, e)
+    }
+}
+
+/// A pluggable way of actually running a build action's command. Swappable
+/// so platforms without Linux namespace support (anything non-Linux) can
+/// fall back to running commands with full ambient access instead.
+pub trait ExecutionBackend: std::fmt::Debug + Send + Sync {
+    /// `extra_env` carries environment entries that must reach the child
+    /// regardless of sandboxing, such as the jobserver's `MAKEFLAGS`/
+    /// `CARGO_MAKEFLAGS` so a spawned `rustc`/`cc` draws from the same
+    /// token pool instead of oversubscribing the machine.
+    fn run(
+        &self,
+        context: &Context,
+        command: &str,
+        args: &[&str],
+        extra_env: &[(String, String)],
+    ) -> std::io::Result<String>;
+}
+
+fn run_direct(
+    context: &Context,
+    command: &str,
+    args: &[&str],
+    envs: &[(&str, &str)],
+) -> std::io::Result<String> {
+    let mut cmd = std::process::Command::new(command);
+    cmd.args(args);
+    cmd.current_dir(context.working_directory());
+    cmd.env_clear();
+    // A minimal, deterministic baseline so commands can still find other
+    // binaries by name; individual `envs` entries below can override it.
+    cmd.env("PATH", "/usr/bin:/bin");
+    for (k, v) in envs {
+        cmd.env(k, v);
+    }
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+
+    let output = cmd.output()?;
+    if !output.status.success() {
+        return Err(ProcessError {
+            command: command.to_string(),
+            status: output.status.code(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        }
+        .into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Runs commands with full ambient access to the host filesystem and
+/// network - no isolation at all. Used on platforms that don't support
+/// Linux namespaces.
+#[derive(Debug, Clone, Default)]
+pub struct NoSandbox {}
+
+impl ExecutionBackend for NoSandbox {
+    fn run(
+        &self,
+        context: &Context,
+        command: &str,
+        args: &[&str],
+        extra_env: &[(String, String)],
+    ) -> std::io::Result<String> {
+        let envs: Vec<(&str, &str)> = extra_env
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        run_direct(context, command, args, &envs)
+    }
+}
+
+/// Runs each build action inside a fresh Linux namespace. `--net` and
+/// `--pid`/`--user` genuinely isolate the command - no network devices, no
+/// visibility into host processes - but a bare mount namespace starts as a
+/// *copy* of the host's mount table, so it does NOT by itself hide anything
+/// on disk. The filesystem isolation comes from `build_isolation_script`
+/// below: it builds a private root under `scratch_dir()` containing only a
+/// read-only view of the host toolchain directories (so `rustc`/`cc`/`tar`
+/// can still find themselves and libc) plus a read-write bind-mount of the
+/// task's own `working_directory()`/`scratch_dir()`, then `chroot`s into it
+/// before exec'ing the command.
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, Default)]
+pub struct NamespaceSandbox {}
+
+#[cfg(target_os = "linux")]
+const SANDBOX_READONLY_HOST_DIRS: &[&str] = &["/usr", "/bin", "/sbin", "/lib", "/lib64", "/etc"];
+
+#[cfg(target_os = "linux")]
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Builds the `sh -c` script run inside the unshared namespaces: bind-mounts
+/// a minimal root under `sandbox_root`, then `chroot`s into it and execs
+/// `command`. Mounting and chrooting from inside the namespace (rather than
+/// from the parent) is what keeps the rest of the host filesystem hidden -
+/// the new mount namespace's table is private, so mutating it here never
+/// touches the real root's mount table.
+#[cfg(target_os = "linux")]
+fn build_isolation_script(
+    sandbox_root: &std::path::Path,
+    working_dir: &std::path::Path,
+    scratch_dir: &std::path::Path,
+    command: &str,
+    args: &[&str],
+) -> String {
+    let root = sandbox_root.to_string_lossy();
+    let mut script = String::new();
+    script.push_str("set -e; ");
+
+    for host_dir in SANDBOX_READONLY_HOST_DIRS {
+        if !std::path::Path::new(host_dir).exists() {
+            continue;
+        }
+        let q = shell_quote(host_dir);
+        script.push_str(&format!(
+            "mkdir -p {root}{host_dir}; mount --rbind {q} {root}{host_dir}; \
+             mount --make-rprivate {root}{host_dir}; \
+             mount -o remount,ro,bind {root}{host_dir}; ",
+        ));
+    }
+
+    // The task's own declared inputs/outputs, bind-mounted at the same
+    // absolute path so `working_directory()` stays meaningful post-chroot -
+    // read-write, since these are the only paths the command may mutate.
+    for dir in [working_dir, scratch_dir] {
+        let d = dir.to_string_lossy();
+        let q = shell_quote(&d);
+        script.push_str(&format!("mkdir -p {root}{d}; mount --bind {q} {root}{d}; "));
+    }
+
+    script.push_str(&format!("exec chroot {} {}", shell_quote(&root), shell_quote(command)));
+    for arg in args {
+        script.push(' ');
+        script.push_str(&shell_quote(arg));
+    }
+    script
+}
+
+#[cfg(target_os = "linux")]
+impl ExecutionBackend for NamespaceSandbox {
+    fn run(
+        &self,
+        context: &Context,
+        command: &str,
+        args: &[&str],
+        extra_env: &[(String, String)],
+    ) -> std::io::Result<String> {
+        let working_dir = context.working_directory();
+        let scratch_dir = context.scratch_dir();
+        std::fs::create_dir_all(&working_dir).ok();
+        std::fs::create_dir_all(&scratch_dir).ok();
+
+        let sandbox_root = scratch_dir.join("sandbox-root");
+        std::fs::create_dir_all(&sandbox_root).ok();
+
+        let script = build_isolation_script(&sandbox_root, &working_dir, &scratch_dir, command, args);
+
+        // `unshare --mount --pid --net --user --map-root-user --fork` gives
+        // the child its own (initially host-identical, but private) mount
+        // table, its own PID namespace, and no network devices at all -
+        // `--map-root-user` is what lets the bind-mounts/chroot above run
+        // without real root, since they only need `CAP_SYS_ADMIN` inside the
+        // new user namespace.
+        let mut envs: Vec<(&str, &str)> = vec![("HOME", "/nonexistent")];
+        envs.extend(extra_env.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+
+        run_direct(
+            context,
+            "unshare",
+            &[
+                "--mount",
+                "--pid",
+                "--net",
+                "--user",
+                "--map-root-user",
+                "--fork",
+                "--kill-child",
+                "--",
+                "sh",
+                "-c",
+                &script,
+            ],
+            &envs,
+        )
+    }
+}
+
+/// Downloads always need real network access, so they bypass whichever
+/// `ExecutionBackend` is configured rather than trying to punch a hole in
+/// the sandbox's network namespace.
+pub(crate) fn download_direct(
+    context: &Context,
+    url: &str,
+    dest: &std::path::Path,
+) -> std::io::Result<()> {
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent).ok();
+    }
+    run_direct(context, "curl", &["-sSL", "-o", &dest.to_string_lossy(), url], &[])?;
+    Ok(())
+}
@@ -0,0 +1,128 @@
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd};
+
+/// Implements the GNU make jobserver protocol (the same one `cargo` itself
+/// speaks) so the scheduler can bound how many `Task`s build concurrently,
+/// and so that whatever parallelism `rustc`/`cc`/a build script spawns on
+/// its own draws from that same pool instead of oversubscribing the
+/// machine.
+///
+/// A jobserver is just a pipe preloaded with `parallelism - 1` single-byte
+/// tokens, plus one implicit token that's never written to the pipe at all -
+/// matching GNU make's own convention, where every job gets to run its
+/// *first* task for free and only needs to read from the pipe for
+/// additional concurrency. Without that, `acquire()` would have to pull a
+/// real token even for the very first task, and at `parallelism == 1` the
+/// pipe starts empty: the first build would block forever.
+#[derive(Debug)]
+pub struct JobServer {
+    read_end: std::fs::File,
+    write_end: std::fs::File,
+    implicit_token_free: std::sync::atomic::AtomicBool,
+}
+
+impl JobServer {
+    pub fn new(parallelism: usize) -> std::io::Result<Self> {
+        let mut fds = [0i32; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        // Safety: `pipe(2)` just gave us these two fresh, valid, unshared
+        // file descriptors; wrapping them in `File` gives us a safe,
+        // close-on-drop handle to each end.
+        let read_end = unsafe { std::fs::File::from_raw_fd(fds[0]) };
+        let write_end = unsafe { std::fs::File::from_raw_fd(fds[1]) };
+
+        for _ in 0..parallelism.saturating_sub(1) {
+            (&write_end).write_all(b"+")?;
+        }
+
+        Ok(Self {
+            read_end,
+            write_end,
+            implicit_token_free: std::sync::atomic::AtomicBool::new(true),
+        })
+    }
+
+    /// Blocks the calling worker until a token is free, then hands back a
+    /// guard that releases it on drop - on success, on error, and on panic,
+    /// since stack unwinding still runs destructors. This is what keeps the
+    /// pool from leaking and deadlocking the graph.
+    ///
+    /// The very first caller to find the implicit token free takes it
+    /// without touching the pipe at all; everyone else reads a real token,
+    /// exactly like a GNU make job asking for help beyond its own slot.
+    pub fn acquire(&self) -> std::io::Result<JobToken<'_>> {
+        if self
+            .implicit_token_free
+            .swap(false, std::sync::atomic::Ordering::AcqRel)
+        {
+            return Ok(JobToken {
+                server: self,
+                implicit: true,
+            });
+        }
+        let mut buf = [0u8; 1];
+        (&self.read_end).read_exact(&mut buf)?;
+        Ok(JobToken {
+            server: self,
+            implicit: false,
+        })
+    }
+
+    /// The `MAKEFLAGS`/`CARGO_MAKEFLAGS` entries to thread through
+    /// `BuildActions::run_process` so a spawned `rustc`/`cc`/recursive
+    /// `make` draws from this same token pool.
+    pub fn env_vars(&self) -> Vec<(String, String)> {
+        let flags = format!(
+            "--jobserver-auth={},{}",
+            self.read_end.as_raw_fd(),
+            self.write_end.as_raw_fd()
+        );
+        vec![
+            ("MAKEFLAGS".to_string(), flags.clone()),
+            ("CARGO_MAKEFLAGS".to_string(), flags),
+        ]
+    }
+}
+
+/// An acquired jobserver token; returns it to the pool when dropped.
+#[derive(Debug)]
+pub struct JobToken<'a> {
+    server: &'a JobServer,
+    implicit: bool,
+}
+
+impl<'a> Drop for JobToken<'a> {
+    fn drop(&mut self) {
+        if self.implicit {
+            self.server
+                .implicit_token_free
+                .store(true, std::sync::atomic::Ordering::Release);
+            return;
+        }
+        // Best-effort: there's nowhere to report a failure from inside
+        // `Drop`, but leaving the token unreleased would leak the pool, so
+        // we still try.
+        let _ = (&self.server.write_end).write_all(b"+");
+    }
+}
+
+/// Runs one `Task` to completion under the jobserver: blocks the calling
+/// worker until a token is free, then invokes `build`. `task` must already
+/// report `TaskStatus::Building` (checked by a debug assertion); this
+/// function does not change `task` itself. The token is released
+/// afterward no matter how `build` returns - including if it panics - so
+/// the pool can never leak and deadlock the graph.
+pub fn build_task<F>(
+    jobserver: &JobServer,
+    task: &crate::core::Task,
+    build: F,
+) -> std::io::Result<crate::core::BuildResult>
+where
+    F: FnOnce() -> crate::core::BuildResult,
+{
+    debug_assert_eq!(task.status(), crate::core::TaskStatus::Building);
+    let _token = jobserver.acquire()?;
+    Ok(build())
+}